@@ -1,6 +1,8 @@
 use crate::error::ExTokenizersError;
 use rustler::resource::ResourceArc;
-use rustler::{Binary, Env};
+use rustler::{Binary, Env, OwnedBinary};
+use std::collections::HashMap;
+use std::ops::Range;
 use tokenizers::utils::padding::PaddingDirection;
 use tokenizers::utils::truncation::TruncationDirection;
 use tokenizers::Encoding;
@@ -91,6 +93,183 @@ pub fn get_u32_special_tokens_mask(
         .make_binary(env, |r| slice_u32_to_u8(r.0.get_special_tokens_mask())))
 }
 
+/// Word-packed export of the attention mask: a run of all-zero 8-byte words
+/// collapses to a tag and a run count, a run of all-nonzero words is copied
+/// verbatim behind a tag and a run count, and any other word keeps only its
+/// nonzero bytes behind a bitmask tag. See `unpack_bytes/1` for the inverse.
+#[rustler::nif]
+pub fn get_packed_attention_mask(
+    env: Env,
+    encoding: ExTokenizersEncoding,
+) -> Result<Binary, ExTokenizersError> {
+    let packed = pack_bytes(slice_u32_to_u8(encoding.resource.0.get_attention_mask()));
+    owned_binary(env, &packed)
+}
+
+/// Word-packed export of the type ids. See `get_packed_attention_mask/1`.
+#[rustler::nif]
+pub fn get_packed_type_ids(
+    env: Env,
+    encoding: ExTokenizersEncoding,
+) -> Result<Binary, ExTokenizersError> {
+    let packed = pack_bytes(slice_u32_to_u8(encoding.resource.0.get_type_ids()));
+    owned_binary(env, &packed)
+}
+
+/// Word-packed export of the special tokens mask. See `get_packed_attention_mask/1`.
+#[rustler::nif]
+pub fn get_packed_special_tokens_mask(
+    env: Env,
+    encoding: ExTokenizersEncoding,
+) -> Result<Binary, ExTokenizersError> {
+    let packed = pack_bytes(slice_u32_to_u8(encoding.resource.0.get_special_tokens_mask()));
+    owned_binary(env, &packed)
+}
+
+/// Inverse of `get_packed_attention_mask/1` and friends: expands a word-packed
+/// blob back into the original raw byte stream.
+#[rustler::nif]
+pub fn unpack_bytes(env: Env, bytes: Binary) -> Result<Binary, ExTokenizersError> {
+    let unpacked = unpack_bytes_impl(bytes.as_slice())?;
+    owned_binary(env, &unpacked)
+}
+
+fn owned_binary<'a>(env: Env<'a>, bytes: &[u8]) -> Result<Binary<'a>, ExTokenizersError> {
+    let mut binary = OwnedBinary::new(bytes.len())
+        .ok_or_else(|| ExTokenizersError::from("failed to allocate binary".to_string()))?;
+    binary.as_mut_slice().copy_from_slice(bytes);
+    Ok(binary.release(env))
+}
+
+fn pack_bytes(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_uleb128(&mut out, data.len() as u64);
+
+    let mut i = 0;
+    while i < data.len() {
+        let word_len = (data.len() - i).min(8);
+        let word = &data[i..i + word_len];
+        let all_zero = word.iter().all(|&b| b == 0);
+        let all_nonzero = word.iter().all(|&b| b != 0);
+
+        if all_zero {
+            let mut count = 0u8;
+            let mut j = i + word_len;
+            while count < 255 && j < data.len() {
+                let next_len = (data.len() - j).min(8);
+                if data[j..j + next_len].iter().all(|&b| b == 0) {
+                    count += 1;
+                    j += next_len;
+                } else {
+                    break;
+                }
+            }
+            out.push(0x00);
+            out.push(count);
+            i = j;
+        } else if all_nonzero && word_len == 8 {
+            let mut count = 0u8;
+            let mut verbatim = Vec::new();
+            let mut j = i + 8;
+            while count < 255 && j < data.len() {
+                let next_len = (data.len() - j).min(8);
+                if data[j..j + next_len].iter().all(|&b| b != 0) {
+                    count += 1;
+                    verbatim.extend_from_slice(&data[j..j + next_len]);
+                    j += next_len;
+                } else {
+                    break;
+                }
+            }
+            out.push(0xFF);
+            out.extend_from_slice(word);
+            out.push(count);
+            out.extend_from_slice(&verbatim);
+            i = j;
+        } else {
+            let mut tag = 0u8;
+            for (bit, &b) in word.iter().enumerate() {
+                if b != 0 {
+                    tag |= 1 << bit;
+                }
+            }
+            out.push(tag);
+            for &b in word {
+                if b != 0 {
+                    out.push(b);
+                }
+            }
+            i += word_len;
+        }
+    }
+
+    out
+}
+
+/// Word-packed runs can legitimately expand a couple of bytes into megabytes
+/// of zeros, so the declared length can't be checked against the remaining
+/// byte count the way the other blob formats are. Cap it instead: this is
+/// far larger than any batch this NIF is meant to serve, but still small
+/// enough to guard against a corrupted length driving an OOM allocation.
+const MAX_UNPACKED_LEN: usize = 1 << 30;
+
+fn unpack_bytes_impl(bytes: &[u8]) -> Result<Vec<u8>, ExTokenizersError> {
+    let mut pos = 0;
+    let total_len = read_uleb128(bytes, &mut pos)? as usize;
+    if total_len > MAX_UNPACKED_LEN {
+        return Err(format!(
+            "packed blob declares an unpacked length of {} bytes, exceeding the {} byte cap",
+            total_len, MAX_UNPACKED_LEN
+        )
+        .into());
+    }
+    let mut out = Vec::with_capacity(total_len);
+
+    while out.len() < total_len {
+        let tag = *bytes
+            .get(pos)
+            .ok_or_else(|| ExTokenizersError::from("truncated packed blob".to_string()))?;
+        pos += 1;
+
+        if tag == 0x00 {
+            let count = *bytes
+                .get(pos)
+                .ok_or_else(|| ExTokenizersError::from("truncated packed blob".to_string()))?;
+            pos += 1;
+            for _ in 0..=count {
+                let word_len = (total_len - out.len()).min(8);
+                out.extend(std::iter::repeat(0u8).take(word_len));
+            }
+        } else if tag == 0xFF {
+            let word_len = (total_len - out.len()).min(8);
+            out.extend_from_slice(read_bytes(bytes, &mut pos, word_len)?);
+            let count = *bytes
+                .get(pos)
+                .ok_or_else(|| ExTokenizersError::from("truncated packed blob".to_string()))?;
+            pos += 1;
+            for _ in 0..count {
+                let word_len = (total_len - out.len()).min(8);
+                out.extend_from_slice(read_bytes(bytes, &mut pos, word_len)?);
+            }
+        } else {
+            let word_len = (total_len - out.len()).min(8);
+            for bit in 0..word_len {
+                if tag & (1 << bit) != 0 {
+                    let b = *bytes.get(pos).ok_or_else(|| {
+                        ExTokenizersError::from("truncated packed blob".to_string())
+                    })?;
+                    pos += 1;
+                    out.push(b);
+                } else {
+                    out.push(0);
+                }
+            }
+        }
+    }
+
+    Ok(out)
+}
+
 #[rustler::nif]
 pub fn get_offsets(
     encoding: ExTokenizersEncoding,
@@ -103,6 +282,79 @@ pub fn n_tokens(encoding: ExTokenizersEncoding) -> Result<usize, ExTokenizersErr
     Ok(encoding.resource.0.len())
 }
 
+#[rustler::nif]
+pub fn get_word_ids(encoding: ExTokenizersEncoding) -> Result<Vec<Option<u32>>, ExTokenizersError> {
+    Ok(encoding.resource.0.get_word_ids().to_vec())
+}
+
+#[rustler::nif]
+pub fn get_sequence_ids(
+    encoding: ExTokenizersEncoding,
+) -> Result<Vec<Option<usize>>, ExTokenizersError> {
+    Ok(encoding.resource.0.get_sequence_ids())
+}
+
+/// Maps a token index to the `(sequence_id, (start, end))` character span it
+/// came from, or `nil` if the index is out of bounds.
+#[rustler::nif]
+pub fn token_to_chars(
+    encoding: ExTokenizersEncoding,
+    token_index: usize,
+) -> Result<Option<(usize, (usize, usize))>, ExTokenizersError> {
+    Ok(encoding.resource.0.token_to_chars(token_index))
+}
+
+/// Maps a token index to the `(sequence_id, word_index)` it belongs to.
+#[rustler::nif]
+pub fn token_to_word(
+    encoding: ExTokenizersEncoding,
+    token_index: usize,
+) -> Result<Option<(usize, u32)>, ExTokenizersError> {
+    Ok(encoding.resource.0.token_to_word(token_index))
+}
+
+/// Maps a character position within the given sequence to its token index.
+#[rustler::nif]
+pub fn char_to_token(
+    encoding: ExTokenizersEncoding,
+    char_pos: usize,
+    sequence_id: usize,
+) -> Result<Option<usize>, ExTokenizersError> {
+    Ok(encoding.resource.0.char_to_token(char_pos, sequence_id))
+}
+
+/// Maps a character position within the given sequence to its word index.
+#[rustler::nif]
+pub fn char_to_word(
+    encoding: ExTokenizersEncoding,
+    char_pos: usize,
+    sequence_id: usize,
+) -> Result<Option<u32>, ExTokenizersError> {
+    Ok(encoding.resource.0.char_to_word(char_pos, sequence_id))
+}
+
+/// Maps a word index within the given sequence to the `(start, end)` span of
+/// token indices that make it up.
+#[rustler::nif]
+pub fn word_to_tokens(
+    encoding: ExTokenizersEncoding,
+    word: u32,
+    sequence_id: usize,
+) -> Result<Option<(usize, usize)>, ExTokenizersError> {
+    Ok(encoding.resource.0.word_to_tokens(word, sequence_id))
+}
+
+/// Maps a word index within the given sequence to its `(start, end)`
+/// character span.
+#[rustler::nif]
+pub fn word_to_chars(
+    encoding: ExTokenizersEncoding,
+    word: u32,
+    sequence_id: usize,
+) -> Result<Option<(usize, usize)>, ExTokenizersError> {
+    Ok(encoding.resource.0.word_to_chars(word, sequence_id))
+}
+
 #[rustler::nif]
 pub fn truncate(
     encoding: ExTokenizersEncoding,
@@ -110,11 +362,7 @@ pub fn truncate(
     stride: usize,
     direction: &str,
 ) -> Result<ExTokenizersEncoding, ExTokenizersError> {
-    let direction: TruncationDirection = match direction {
-        "left" => TruncationDirection::Left,
-        "right" => TruncationDirection::Right,
-        _ => panic!("direction must be right or left"),
-    };
+    let direction = parse_truncation_direction(direction)?;
     let mut new_encoding = encoding.resource.0.clone();
     new_encoding.truncate(max_len, stride, direction);
     Ok(ExTokenizersEncoding::new(new_encoding))
@@ -129,16 +377,603 @@ pub fn pad(
     pad_token: &str,
     direction: &str,
 ) -> Result<ExTokenizersEncoding, ExTokenizersError> {
-    let direction: PaddingDirection = match direction {
-        "left" => PaddingDirection::Left,
-        "right" => PaddingDirection::Right,
-        _ => panic!("direction must be right or left"),
-    };
+    let direction = parse_padding_direction(direction)?;
     let mut new_encoding = encoding.resource.0.clone();
     new_encoding.pad(target_length, pad_id, pad_type_id, pad_token, direction);
     Ok(ExTokenizersEncoding::new(new_encoding))
 }
 
+/// Truncates every encoding in a batch to at most `max_len` tokens in one NIF
+/// call; shorter encodings keep their length. `max_len` defaults to the
+/// longest encoding in the batch when absent.
+#[rustler::nif]
+pub fn truncate_batch(
+    encodings: Vec<ExTokenizersEncoding>,
+    max_len: Option<usize>,
+    stride: usize,
+    direction: &str,
+) -> Result<Vec<ExTokenizersEncoding>, ExTokenizersError> {
+    let direction = parse_truncation_direction(direction)?;
+    let max_len = max_len
+        .unwrap_or_else(|| longest_len(encodings.iter().map(|encoding| &encoding.resource.0)));
+
+    Ok(encodings
+        .iter()
+        .map(|encoding| {
+            let mut new_encoding = encoding.resource.0.clone();
+            new_encoding.truncate(max_len, stride, direction);
+            ExTokenizersEncoding::new(new_encoding)
+        })
+        .collect())
+}
+
+/// Pads a whole batch of encodings to a common length in one NIF call,
+/// defaulting `target_length` to the longest encoding in the batch ("longest"
+/// mode) when absent.
+#[rustler::nif]
+pub fn pad_batch(
+    encodings: Vec<ExTokenizersEncoding>,
+    target_length: Option<usize>,
+    pad_id: u32,
+    pad_type_id: u32,
+    pad_token: &str,
+    direction: &str,
+) -> Result<Vec<ExTokenizersEncoding>, ExTokenizersError> {
+    let direction = parse_padding_direction(direction)?;
+    let target_length = target_length
+        .unwrap_or_else(|| longest_len(encodings.iter().map(|encoding| &encoding.resource.0)));
+
+    Ok(encodings
+        .iter()
+        .map(|encoding| {
+            let mut new_encoding = encoding.resource.0.clone();
+            new_encoding.pad(target_length, pad_id, pad_type_id, pad_token, direction);
+            ExTokenizersEncoding::new(new_encoding)
+        })
+        .collect())
+}
+
+/// Length of the longest encoding, or 0 when there are none.
+fn longest_len<'a>(encodings: impl IntoIterator<Item = &'a Encoding>) -> usize {
+    encodings.into_iter().map(Encoding::len).max().unwrap_or(0)
+}
+
+fn parse_truncation_direction(direction: &str) -> Result<TruncationDirection, ExTokenizersError> {
+    match direction {
+        "left" => Ok(TruncationDirection::Left),
+        "right" => Ok(TruncationDirection::Right),
+        other => Err(format!("direction must be right or left, got {:?}", other).into()),
+    }
+}
+
+fn parse_padding_direction(direction: &str) -> Result<PaddingDirection, ExTokenizersError> {
+    match direction {
+        "left" => Ok(PaddingDirection::Left),
+        "right" => Ok(PaddingDirection::Right),
+        other => Err(format!("direction must be right or left, got {:?}", other).into()),
+    }
+}
+
+/// Exports `ids`, `attention_mask`, and `type_ids` for a whole batch of
+/// encodings in a single NIF crossing: one contiguous binary laid out as a
+/// rectangular struct-of-arrays (all ids, then all attention masks, then all
+/// type ids, each row zero-padded/truncated to `target_length`), plus the
+/// batch dims so the caller can `Nx.from_binary` and reshape once.
+#[rustler::nif]
+pub fn get_all_u32(
+    env: Env,
+    encodings: Vec<ExTokenizersEncoding>,
+    target_length: usize,
+) -> Result<(Binary, usize, usize), ExTokenizersError> {
+    let batch_size = encodings.len();
+    let rows: Vec<[&[u32]; 3]> = encodings
+        .iter()
+        .map(|encoding| {
+            let encoding = &encoding.resource.0;
+            [
+                encoding.get_ids(),
+                encoding.get_attention_mask(),
+                encoding.get_type_ids(),
+            ]
+        })
+        .collect();
+
+    let mut binary = OwnedBinary::new(3 * batch_size * target_length * std::mem::size_of::<u32>())
+        .ok_or_else(|| ExTokenizersError::from("failed to allocate batch binary".to_string()))?;
+    binary.as_mut_slice().fill(0);
+    write_u32_columns(binary.as_mut_slice(), &rows, target_length);
+
+    Ok((binary.release(env), batch_size, target_length))
+}
+
+/// Writes `rows` (one `[ids, attention_mask, type_ids]` triple per encoding)
+/// into `out` as a rectangular struct-of-arrays: all ids, then all attention
+/// masks, then all type ids, each row zero-padded/truncated to
+/// `target_length`. `out` must already be zeroed and sized for
+/// `3 * rows.len() * target_length` `u32`s; copies straight from each
+/// encoding's own slice, no intermediate `Vec<u32>`.
+fn write_u32_columns(out: &mut [u8], rows: &[[&[u32]; 3]], target_length: usize) {
+    const U32_SIZE: usize = std::mem::size_of::<u32>();
+    let batch_size = rows.len();
+
+    for col in 0..3 {
+        let col_offset = col * batch_size * target_length;
+        for (row, columns) in rows.iter().enumerate() {
+            let values = columns[col];
+            let n = values.len().min(target_length);
+            let row_offset = (col_offset + row * target_length) * U32_SIZE;
+            out[row_offset..row_offset + n * U32_SIZE].copy_from_slice(slice_u32_to_u8(&values[..n]));
+        }
+    }
+}
+
 fn slice_u32_to_u8(slice: &[u32]) -> &[u8] {
     unsafe { std::slice::from_raw_parts(slice.as_ptr() as *const u8, slice.len() * 4) }
 }
+
+/// Serializes a whole `Encoding` (including any overflowing encodings) into a
+/// compact, self-describing binary blob: a LEB128-varint column per field,
+/// with the `offsets` column delta-encoded (a decreasing offset, such as a
+/// special token's `(0, 0)`, wraps around and still round-trips), `tokens` stored as
+/// length-prefixed UTF-8, and per-token sequence ids carried alongside the
+/// word ids so sequence ranges survive the round trip. See `deserialize/1`
+/// for the inverse.
+#[rustler::nif]
+pub fn serialize(env: Env, encoding: ExTokenizersEncoding) -> Result<Binary, ExTokenizersError> {
+    let bytes = encode_encoding(&encoding.resource.0);
+    owned_binary(env, &bytes)
+}
+
+#[rustler::nif]
+pub fn deserialize(bytes: Binary) -> Result<ExTokenizersEncoding, ExTokenizersError> {
+    let encoding = decode_encoding(bytes.as_slice())?;
+    Ok(ExTokenizersEncoding::new(encoding))
+}
+
+fn write_uleb128(buf: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let mut byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value != 0 {
+            byte |= 0x80;
+        }
+        buf.push(byte);
+        if value == 0 {
+            break;
+        }
+    }
+}
+
+fn read_uleb128(bytes: &[u8], pos: &mut usize) -> Result<u64, ExTokenizersError> {
+    let mut result: u64 = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *bytes
+            .get(*pos)
+            .ok_or_else(|| ExTokenizersError::from("truncated encoding blob".to_string()))?;
+        *pos += 1;
+        result |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    Ok(result)
+}
+
+fn read_bytes<'a>(bytes: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8], ExTokenizersError> {
+    let end = *pos + len;
+    let slice = bytes
+        .get(*pos..end)
+        .ok_or_else(|| ExTokenizersError::from("truncated encoding blob".to_string()))?;
+    *pos = end;
+    Ok(slice)
+}
+
+/// Rejects a declared column/collection length before it is used to size a
+/// `Vec::with_capacity` allocation. Every entry in these columns takes at
+/// least one byte on the wire, so a length that outruns the remaining bytes
+/// can only be a truncated or corrupted blob, not a legitimate huge batch.
+fn check_declared_len(declared: usize, remaining: usize) -> Result<(), ExTokenizersError> {
+    if declared > remaining {
+        Err(format!(
+            "encoding blob declares a length of {} but only {} bytes remain",
+            declared, remaining
+        )
+        .into())
+    } else {
+        Ok(())
+    }
+}
+
+fn encode_encoding(encoding: &Encoding) -> Vec<u8> {
+    let mut buf = Vec::new();
+
+    let ids = encoding.get_ids();
+    write_uleb128(&mut buf, ids.len() as u64);
+    for &id in ids {
+        write_uleb128(&mut buf, id as u64);
+    }
+    for &type_id in encoding.get_type_ids() {
+        write_uleb128(&mut buf, type_id as u64);
+    }
+    for &mask in encoding.get_special_tokens_mask() {
+        write_uleb128(&mut buf, mask as u64);
+    }
+    for &mask in encoding.get_attention_mask() {
+        write_uleb128(&mut buf, mask as u64);
+    }
+    for word_id in encoding.get_word_ids() {
+        match word_id {
+            Some(id) => write_uleb128(&mut buf, *id as u64 + 1),
+            None => write_uleb128(&mut buf, 0),
+        }
+    }
+    for sequence_id in encoding.get_sequence_ids() {
+        match sequence_id {
+            Some(id) => write_uleb128(&mut buf, id as u64 + 1),
+            None => write_uleb128(&mut buf, 0),
+        }
+    }
+
+    let mut prev_start = 0u64;
+    let mut prev_end = 0u64;
+    for &(start, end) in encoding.get_offsets() {
+        let (start, end) = (start as u64, end as u64);
+        write_uleb128(&mut buf, start.wrapping_sub(prev_start));
+        write_uleb128(&mut buf, end.wrapping_sub(prev_end));
+        prev_start = start;
+        prev_end = end;
+    }
+
+    for token in encoding.get_tokens() {
+        let token_bytes = token.as_bytes();
+        write_uleb128(&mut buf, token_bytes.len() as u64);
+        buf.extend_from_slice(token_bytes);
+    }
+
+    let overflowing = encoding.get_overflowing();
+    write_uleb128(&mut buf, overflowing.len() as u64);
+    for sub_encoding in overflowing {
+        let sub_bytes = encode_encoding(sub_encoding);
+        write_uleb128(&mut buf, sub_bytes.len() as u64);
+        buf.extend_from_slice(&sub_bytes);
+    }
+
+    buf
+}
+
+/// Caps how many `overflowing` levels `decode_encoding_at` will recurse
+/// through. Each level costs only a few bytes on the wire, so without a limit
+/// a crafted blob could recurse deep enough to overflow the native stack.
+const MAX_OVERFLOW_DEPTH: usize = 32;
+
+fn decode_encoding(bytes: &[u8]) -> Result<Encoding, ExTokenizersError> {
+    let mut pos = 0;
+    decode_encoding_at(bytes, &mut pos, 0)
+}
+
+fn decode_encoding_at(
+    bytes: &[u8],
+    pos: &mut usize,
+    depth: usize,
+) -> Result<Encoding, ExTokenizersError> {
+    if depth > MAX_OVERFLOW_DEPTH {
+        return Err(format!(
+            "encoding blob nests more than {} overflowing levels deep",
+            MAX_OVERFLOW_DEPTH
+        )
+        .into());
+    }
+
+    let n = read_uleb128(bytes, pos)? as usize;
+    check_declared_len(n, bytes.len().saturating_sub(*pos))?;
+
+    let mut ids = Vec::with_capacity(n);
+    for _ in 0..n {
+        ids.push(read_uleb128(bytes, pos)? as u32);
+    }
+    let mut type_ids = Vec::with_capacity(n);
+    for _ in 0..n {
+        type_ids.push(read_uleb128(bytes, pos)? as u32);
+    }
+    let mut special_tokens_mask = Vec::with_capacity(n);
+    for _ in 0..n {
+        special_tokens_mask.push(read_uleb128(bytes, pos)? as u32);
+    }
+    let mut attention_mask = Vec::with_capacity(n);
+    for _ in 0..n {
+        attention_mask.push(read_uleb128(bytes, pos)? as u32);
+    }
+    let mut words = Vec::with_capacity(n);
+    for _ in 0..n {
+        let raw = read_uleb128(bytes, pos)?;
+        words.push(if raw == 0 { None } else { Some((raw - 1) as u32) });
+    }
+    let mut sequence_ids = Vec::with_capacity(n);
+    for _ in 0..n {
+        let raw = read_uleb128(bytes, pos)?;
+        sequence_ids.push(if raw == 0 { None } else { Some((raw - 1) as usize) });
+    }
+
+    let mut offsets = Vec::with_capacity(n);
+    let mut prev_start = 0u64;
+    let mut prev_end = 0u64;
+    for _ in 0..n {
+        prev_start = prev_start.wrapping_add(read_uleb128(bytes, pos)?);
+        prev_end = prev_end.wrapping_add(read_uleb128(bytes, pos)?);
+        offsets.push((prev_start as usize, prev_end as usize));
+    }
+
+    let mut tokens = Vec::with_capacity(n);
+    for _ in 0..n {
+        let len = read_uleb128(bytes, pos)? as usize;
+        let token_bytes = read_bytes(bytes, pos, len)?;
+        let token = String::from_utf8(token_bytes.to_vec())
+            .map_err(|_| ExTokenizersError::from("invalid utf-8 in encoding blob".to_string()))?;
+        tokens.push(token);
+    }
+
+    let overflow_count = read_uleb128(bytes, pos)? as usize;
+    check_declared_len(overflow_count, bytes.len().saturating_sub(*pos))?;
+    let mut overflowing = Vec::with_capacity(overflow_count);
+    for _ in 0..overflow_count {
+        let len = read_uleb128(bytes, pos)? as usize;
+        let sub_bytes = read_bytes(bytes, pos, len)?;
+        let mut sub_pos = 0;
+        overflowing.push(decode_encoding_at(sub_bytes, &mut sub_pos, depth + 1)?);
+    }
+
+    Ok(Encoding::new(
+        ids,
+        type_ids,
+        tokens,
+        words,
+        offsets,
+        special_tokens_mask,
+        attention_mask,
+        overflowing,
+        sequence_ranges_from_ids(&sequence_ids),
+    ))
+}
+
+/// Rebuilds the `sequence_ranges` map from the per-token sequence ids that
+/// `encode_encoding` stores alongside the word ids. Tokenizers always lays a
+/// sequence out as one contiguous span of tokens, so the first and last
+/// occurrence of each sequence id fully determine its range.
+fn sequence_ranges_from_ids(sequence_ids: &[Option<usize>]) -> HashMap<usize, Range<usize>> {
+    let mut ranges: HashMap<usize, Range<usize>> = HashMap::new();
+    for (i, sequence_id) in sequence_ids.iter().enumerate() {
+        if let Some(id) = sequence_id {
+            ranges
+                .entry(*id)
+                .and_modify(|range| range.end = i + 1)
+                .or_insert(i..i + 1);
+        }
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_encoding(overflowing: Vec<Encoding>) -> Encoding {
+        let mut sequence_ranges = HashMap::new();
+        sequence_ranges.insert(0, 0..3);
+        sequence_ranges.insert(1, 3..5);
+
+        Encoding::new(
+            vec![10, 11, 12, 13, 14],
+            vec![0, 0, 0, 1, 1],
+            vec![
+                "foo".to_string(),
+                "bar".to_string(),
+                "baz".to_string(),
+                "qux".to_string(),
+                "quux".to_string(),
+            ],
+            vec![Some(0), Some(0), None, Some(1), Some(1)],
+            vec![(0, 3), (3, 6), (6, 6), (7, 10), (10, 14)],
+            vec![0, 0, 0, 0, 1],
+            vec![1, 1, 1, 1, 1],
+            overflowing,
+            sequence_ranges,
+        )
+    }
+
+    #[test]
+    fn serialize_round_trip_preserves_encoding() {
+        let original = sample_encoding(vec![]);
+        let bytes = encode_encoding(&original);
+        let decoded = decode_encoding(&bytes).unwrap();
+
+        assert_eq!(decoded.get_ids(), original.get_ids());
+        assert_eq!(decoded.get_type_ids(), original.get_type_ids());
+        assert_eq!(decoded.get_tokens(), original.get_tokens());
+        assert_eq!(decoded.get_word_ids(), original.get_word_ids());
+        assert_eq!(decoded.get_offsets(), original.get_offsets());
+        assert_eq!(
+            decoded.get_special_tokens_mask(),
+            original.get_special_tokens_mask()
+        );
+        assert_eq!(decoded.get_attention_mask(), original.get_attention_mask());
+        assert_eq!(decoded.get_sequence_ids(), original.get_sequence_ids());
+    }
+
+    #[test]
+    fn serialize_round_trip_preserves_overflowing() {
+        let original = sample_encoding(vec![sample_encoding(vec![])]);
+        let bytes = encode_encoding(&original);
+        let decoded = decode_encoding(&bytes).unwrap();
+
+        assert_eq!(decoded.get_overflowing().len(), 1);
+        assert_eq!(
+            decoded.get_overflowing()[0].get_ids(),
+            original.get_overflowing()[0].get_ids()
+        );
+        assert_eq!(
+            decoded.get_overflowing()[0].get_sequence_ids(),
+            original.get_overflowing()[0].get_sequence_ids()
+        );
+    }
+
+    #[test]
+    fn decode_encoding_rejects_declared_length_past_end_of_blob() {
+        let mut bytes = Vec::new();
+        write_uleb128(&mut bytes, u32::MAX as u64);
+        assert!(decode_encoding(&bytes).is_err());
+    }
+
+    #[test]
+    fn decode_encoding_rejects_overflow_nesting_past_depth_limit() {
+        // innermost encoding: n = 0, overflow_count = 0
+        let mut blob = Vec::new();
+        write_uleb128(&mut blob, 0);
+        write_uleb128(&mut blob, 0);
+
+        // wrap it in more levels of overflowing than the decoder allows
+        for _ in 0..(MAX_OVERFLOW_DEPTH + 2) {
+            let mut wrapper = Vec::new();
+            write_uleb128(&mut wrapper, 0); // n = 0
+            write_uleb128(&mut wrapper, 1); // overflow_count = 1
+            write_uleb128(&mut wrapper, blob.len() as u64);
+            wrapper.extend_from_slice(&blob);
+            blob = wrapper;
+        }
+
+        assert!(decode_encoding(&blob).is_err());
+    }
+
+    #[test]
+    fn pack_unpack_round_trip_handles_zero_and_nonzero_runs() {
+        let mut data = Vec::new();
+        data.extend(std::iter::repeat(0u8).take(16)); // spans a zero run across words
+        data.extend_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]); // all-nonzero word
+        data.extend_from_slice(&[9, 10, 11, 12, 13, 14, 15, 16]); // joins the nonzero run
+        data.extend_from_slice(&[0, 1, 0, 2, 0, 3]); // trailing partial mixed word
+
+        let packed = pack_bytes(&data);
+        let unpacked = unpack_bytes_impl(&packed).unwrap();
+
+        assert_eq!(unpacked, data);
+    }
+
+    #[test]
+    fn pack_unpack_round_trip_handles_empty_input() {
+        let data: Vec<u8> = vec![];
+        let packed = pack_bytes(&data);
+        let unpacked = unpack_bytes_impl(&packed).unwrap();
+        assert_eq!(unpacked, data);
+    }
+
+    #[test]
+    fn unpack_bytes_rejects_length_past_sane_cap() {
+        let mut bytes = Vec::new();
+        write_uleb128(&mut bytes, MAX_UNPACKED_LEN as u64 + 1);
+        assert!(unpack_bytes_impl(&bytes).is_err());
+    }
+
+    #[test]
+    fn write_u32_columns_pads_and_truncates_rows_per_column() {
+        let target_length = 4;
+        let row0: [&[u32]; 3] = [&[1, 2], &[1, 1], &[0, 0]]; // shorter than target_length: padded
+        let row1: [&[u32]; 3] = [&[9, 9, 9, 9, 9], &[1, 1, 1, 1, 1], &[0, 0, 0, 0, 0]]; // longer: truncated
+        let rows = [row0, row1];
+
+        let mut out = vec![0u8; 3 * rows.len() * target_length * std::mem::size_of::<u32>()];
+        write_u32_columns(&mut out, &rows, target_length);
+
+        let words: Vec<u32> = out
+            .chunks_exact(4)
+            .map(|b| u32::from_ne_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+
+        // column 0 (ids): row0 padded with zeros, row1 truncated to target_length
+        assert_eq!(&words[0..4], &[1, 2, 0, 0]);
+        assert_eq!(&words[4..8], &[9, 9, 9, 9]);
+        // column 1 (attention_mask)
+        assert_eq!(&words[8..12], &[1, 1, 0, 0]);
+        assert_eq!(&words[12..16], &[1, 1, 1, 1]);
+        // column 2 (type_ids)
+        assert_eq!(&words[16..20], &[0, 0, 0, 0]);
+        assert_eq!(&words[20..24], &[0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn alignment_helpers_map_tokens_words_and_chars() {
+        let encoding = sample_encoding(vec![]);
+
+        assert_eq!(encoding.token_to_word(0), Some((0, 0)));
+        assert_eq!(encoding.token_to_chars(0), Some((0, (0, 3))));
+        assert_eq!(encoding.char_to_token(4, 0), Some(1));
+        assert_eq!(encoding.char_to_word(4, 0), Some(0));
+        assert_eq!(encoding.word_to_tokens(1, 1), Some((3, 5)));
+        assert_eq!(encoding.word_to_chars(1, 1), Some((7, 14)));
+    }
+
+    fn encoding_with_len(n: usize) -> Encoding {
+        let mut sequence_ranges = HashMap::new();
+        if n > 0 {
+            sequence_ranges.insert(0, 0..n);
+        }
+
+        Encoding::new(
+            (0..n as u32).collect(),
+            vec![0; n],
+            (0..n).map(|i| format!("t{}", i)).collect(),
+            (0..n).map(|i| Some(i as u32)).collect(),
+            (0..n).map(|i| (i, i + 1)).collect(),
+            vec![0; n],
+            vec![1; n],
+            vec![],
+            sequence_ranges,
+        )
+    }
+
+    // The `#[rustler::nif]` functions themselves can only run inside the BEAM,
+    // so these tests exercise the helpers that `pad`, `truncate`, `pad_batch`
+    // and `truncate_batch` delegate to.
+
+    #[test]
+    fn parse_padding_direction_returns_error_instead_of_panicking() {
+        assert!(parse_padding_direction("up").is_err());
+    }
+
+    #[test]
+    fn parse_truncation_direction_returns_error_instead_of_panicking() {
+        assert!(parse_truncation_direction("up").is_err());
+    }
+
+    #[test]
+    fn longest_len_default_pads_batch_to_longest() {
+        let mut batch = vec![encoding_with_len(2), encoding_with_len(5)];
+
+        let target_length = longest_len(&batch);
+        for encoding in batch.iter_mut() {
+            encoding.pad(target_length, 0, 0, "[PAD]", PaddingDirection::Right);
+        }
+
+        assert_eq!(batch[0].len(), 5);
+        assert_eq!(batch[1].len(), 5);
+    }
+
+    #[test]
+    fn longest_len_default_truncates_nothing() {
+        let mut batch = vec![encoding_with_len(2), encoding_with_len(5)];
+
+        let max_len = longest_len(&batch);
+        for encoding in batch.iter_mut() {
+            encoding.truncate(max_len, 0, TruncationDirection::Right);
+        }
+
+        assert_eq!(batch[0].len(), 2);
+        assert_eq!(batch[1].len(), 5);
+    }
+
+    #[test]
+    fn parse_directions_reject_bad_direction() {
+        assert!(parse_padding_direction("up").is_err());
+        assert!(parse_truncation_direction("up").is_err());
+    }
+}